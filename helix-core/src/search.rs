@@ -65,9 +65,17 @@ pub fn find_nth_prev(
 use crate::movement::Direction;
 use regex_automata::{dense, DenseDFA, Error as RegexError, DFA};
 use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// How many bytes to scan between checks of the cancellation flag.
+///
+/// Checking on every byte would make the DFA walk noticeably slower; this is
+/// a compromise between responsiveness to cancellation and throughput.
+const CANCEL_CHECK_INTERVAL: usize = 4096;
 
 /// Based on https://github.com/alacritty/alacritty/blob/3e867a056018c507d79396cb5c5b4b8309c609c2/alacritty_terminal/src/term/search.rs
-struct Searcher {
+pub struct Searcher {
     /// Locate end of match searching right.
     right_fdfa: DenseDFA<Vec<usize>, usize>,
     /// Locate start of match searching right.
@@ -111,16 +119,43 @@ impl Searcher {
     }
     pub fn search_prev(&self, text: RopeSlice, offset: usize) -> Option<Range<usize>> {
         let text = text.slice(..offset);
-        let start = self.rfind(text, &self.left_fdfa)?;
-        let end = self.find(text.slice(start..), &self.left_rdfa)?;
+        let start = self.rfind(text, &self.left_fdfa, None)?;
+        let end = self.find(text.slice(start..), &self.left_rdfa, None)?;
 
         Some(start..start + end)
     }
 
     pub fn search_next(&self, text: RopeSlice, offset: usize) -> Option<Range<usize>> {
         let text = text.slice(offset..);
-        let end = self.find(text, &self.right_fdfa)?;
-        let start = self.rfind(text.slice(..end), &self.right_rdfa)?;
+        let end = self.find(text, &self.right_fdfa, None)?;
+        let start = self.rfind(text.slice(..end), &self.right_rdfa, None)?;
+
+        Some(offset + start..offset + end)
+    }
+
+    /// Like [`Self::search_next`], but bails out early once `cancelled` is
+    /// set, checking it periodically during the byte-level DFA walk and
+    /// again at the match boundary. Used by [`WorkspaceSearcher`] so a
+    /// search over many documents can be aborted promptly when the user
+    /// edits the pattern.
+    pub fn search_next_cancellable(
+        &self,
+        text: RopeSlice,
+        offset: usize,
+        cancelled: &AtomicBool,
+    ) -> Option<Range<usize>> {
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let text = text.slice(offset..);
+        let end = self.find(text, &self.right_fdfa, Some(cancelled))?;
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let start = self.rfind(text.slice(..end), &self.right_rdfa, Some(cancelled))?;
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
 
         Some(offset + start..offset + end)
     }
@@ -128,7 +163,7 @@ impl Searcher {
     /// Find the next regex match.
     ///
     /// This will always return the side of the first match which is farthest from the start point.
-    fn find(&self, text: RopeSlice, dfa: &impl DFA) -> Option<usize> {
+    fn find(&self, text: RopeSlice, dfa: &impl DFA, cancelled: Option<&AtomicBool>) -> Option<usize> {
         // TOOD: needs to change to rfind condition if searching reverse
         // TODO: check this inside main search
         // if dfa.is_anchored() && start > 0 {
@@ -144,6 +179,7 @@ impl Searcher {
             None
         };
 
+        let mut since_check = 0;
         for chunk in text.chunks() {
             for (i, &b) in chunk.as_bytes().iter().enumerate() {
                 state = unsafe { dfa.next_state_unchecked(state, b) };
@@ -153,13 +189,23 @@ impl Searcher {
                     }
                     last_match = Some(i + 1);
                 }
+
+                since_check += 1;
+                if since_check >= CANCEL_CHECK_INTERVAL {
+                    since_check = 0;
+                    if let Some(cancelled) = cancelled {
+                        if cancelled.load(Ordering::Relaxed) {
+                            return last_match;
+                        }
+                    }
+                }
             }
         }
 
         last_match
     }
 
-    fn rfind(&self, text: RopeSlice, dfa: &impl DFA) -> Option<usize> {
+    fn rfind(&self, text: RopeSlice, dfa: &impl DFA, cancelled: Option<&AtomicBool>) -> Option<usize> {
         // if dfa.is_anchored() && start < bytes.len() {
         //     return None;
         // }
@@ -176,6 +222,7 @@ impl Searcher {
         // This is basically chunks().rev()
         let (mut chunks, _, _, _) = text.chunks_at_byte(text.len_bytes());
 
+        let mut since_check = 0;
         while let Some(chunk) = chunks.prev() {
             for (i, &b) in chunk.as_bytes().iter().enumerate().rev() {
                 state = unsafe { dfa.next_state_unchecked(state, b) };
@@ -185,12 +232,127 @@ impl Searcher {
                     }
                     last_match = Some(i);
                 }
+
+                since_check += 1;
+                if since_check >= CANCEL_CHECK_INTERVAL {
+                    since_check = 0;
+                    if let Some(cancelled) = cancelled {
+                        if cancelled.load(Ordering::Relaxed) {
+                            return last_match;
+                        }
+                    }
+                }
             }
         }
         last_match
     }
 }
 
+/// A single match found while searching across a workspace.
+#[derive(Debug, Clone)]
+pub struct WorkspaceMatch<Id> {
+    pub doc_id: Id,
+    pub range: Range<usize>,
+}
+
+/// A handle to an in-flight [`WorkspaceSearcher`] search.
+///
+/// Matches are streamed over `results` as they are found rather than
+/// collected up front, so the UI can start showing hits in huge projects
+/// without waiting for the whole workspace to be scanned. Dropping the
+/// handle does not itself stop the background task (the channel sender
+/// only notices on its next send); call [`Self::cancel`] to stop promptly,
+/// e.g. when the user edits the search pattern.
+pub struct WorkspaceSearchHandle<Id> {
+    pub results: tokio::sync::mpsc::Receiver<WorkspaceMatch<Id>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<Id> WorkspaceSearchHandle<Id> {
+    /// Stop the search. The result channel closes promptly afterwards, so
+    /// a subsequent `recv` reliably returns `None` and the receiver slot is
+    /// safe to reuse for the next query.
+    pub fn cancel(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        // Swap in a receiver whose sender is already dropped: matches the
+        // task had buffered go away with the old receiver, and the task's
+        // next send fails.
+        let (_, closed) = tokio::sync::mpsc::channel(1);
+        self.results = closed;
+    }
+}
+
+/// Runs a compiled [`Searcher`] over many documents at once, streaming
+/// matches back over a bounded channel instead of collecting them all
+/// before returning.
+pub struct WorkspaceSearcher {
+    searcher: Arc<Searcher>,
+}
+
+impl WorkspaceSearcher {
+    pub fn new(searcher: Searcher) -> Self {
+        Self {
+            searcher: Arc::new(searcher),
+        }
+    }
+
+    /// Spawn a task that scans `documents` in order, reporting every match
+    /// as `(doc_id, byte_range)` over the returned handle's channel. Each
+    /// document is searched by repeatedly calling `search_next_cancellable`
+    /// and advancing the offset past the previous match.
+    pub fn search<Id, I>(&self, documents: I) -> WorkspaceSearchHandle<Id>
+    where
+        Id: Clone + Send + 'static,
+        I: IntoIterator<Item = (Id, crate::Rope)> + Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let searcher = Arc::clone(&self.searcher);
+        let task_cancelled = Arc::clone(&cancelled);
+
+        tokio::spawn(async move {
+            for (doc_id, text) in documents {
+                if task_cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                let mut offset = 0;
+                while let Some(range) =
+                    searcher.search_next_cancellable(text.slice(..), offset, &task_cancelled)
+                {
+                    offset = if range.end > range.start {
+                        range.end
+                    } else {
+                        // A zero-width match (e.g. pattern `a*`) doesn't
+                        // advance the search position on its own, which
+                        // would otherwise spin forever on the same offset.
+                        // Step forward by one char so the next call makes
+                        // progress.
+                        match text.try_byte_to_char(range.end) {
+                            Ok(char_idx) if char_idx + 1 <= text.len_chars() => {
+                                text.char_to_byte(char_idx + 1)
+                            }
+                            _ => break,
+                        }
+                    };
+                    let result = WorkspaceMatch {
+                        doc_id: doc_id.clone(),
+                        range,
+                    };
+                    if tx.send(result).await.is_err() || task_cancelled.load(Ordering::Relaxed) {
+                        return;
+                    }
+                }
+            }
+        });
+
+        WorkspaceSearchHandle {
+            results: rx,
+            cancelled,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -234,4 +396,54 @@ mod test {
         let result = searcher.search_prev(text.slice(..), result.start);
         assert!(result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_workspace_search_streams_all_documents() {
+        use crate::Rope;
+
+        let docs = vec![
+            (0usize, Rope::from("hello world")),
+            (1usize, Rope::from("world peace")),
+        ];
+
+        let searcher = Searcher::new(r"world").unwrap();
+        let workspace = WorkspaceSearcher::new(searcher);
+        let mut handle = workspace.search(docs);
+
+        let mut found = Vec::new();
+        while let Some(result) = handle.results.recv().await {
+            found.push((result.doc_id, result.range));
+        }
+
+        assert_eq!(found, vec![(0, 6..11), (1, 0..5)]);
+    }
+
+    #[tokio::test]
+    async fn test_workspace_search_cancel_closes_channel() {
+        use crate::Rope;
+
+        // Large enough that if cancellation were a no-op, draining the
+        // channel to completion would deliver every one of these matches.
+        const TOTAL_MATCHES: usize = 200_000;
+        let text = "world ".repeat(TOTAL_MATCHES);
+        let docs = vec![(0usize, Rope::from(text.as_str()))];
+
+        let searcher = Searcher::new(r"world").unwrap();
+        let workspace = WorkspaceSearcher::new(searcher);
+        let mut handle = workspace.search(docs);
+
+        handle.cancel();
+
+        let mut received = 0;
+        while handle.results.recv().await.is_some() {
+            received += 1;
+        }
+
+        // Once cancelled the task must stop delivering results and close
+        // the channel promptly, well short of scanning the whole document.
+        assert!(
+            received < TOTAL_MATCHES,
+            "expected cancellation to cut the stream short, got all {received} matches"
+        );
+    }
 }
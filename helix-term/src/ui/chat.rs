@@ -3,12 +3,14 @@ use helix_event::request_redraw;
 use helix_view::{
     chat::{
         self,
-        chat_state::{ChatState, Message},
+        chat_state::{ChatState, Message, PendingReview},
     },
+    editor::Action,
     graphics::{CursorKind, Modifier, Rect},
     theme::Style,
     Editor, ViewId,
 };
+use futures_util::FutureExt;
 use tui::{
     buffer::Buffer as Surface,
     widgets::{Block, BorderType, Widget},
@@ -19,11 +21,14 @@ use crate::{
     ctrl,
     job::Callback,
     key, shift,
-    ui::{overlay::Overlay, Popup},
+    ui::{overlay::Overlay, Picker, Popup},
 };
 
 use crate::ui::markdown::Markdown;
-use std::{path::Path, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use super::{completers, Prompt, PromptEvent};
 
@@ -62,6 +67,7 @@ impl Chat {
         state.context.clear();
         state.context.push(current_context);
         state.context.push(chat::context::Context::Selection);
+        state.set_project_context_enabled(true);
 
         chat
     }
@@ -71,7 +77,9 @@ impl Chat {
     /// Quick chats:
     /// - Do not have any persistent history, when you close them they are gone
     /// - Include the current selection in the context
-    /// - directly applies the edit.
+    /// - start a hunk-by-hunk review of the edit as soon as the response
+    ///   finishes (a regular chat starts one on `ctrl-y`), rather than
+    ///   applying it outright.
     pub fn new_quick(ed: &mut Editor) -> Self {
         let (view, doc) = current!(ed);
         let current_context = chat::context::Context::document(doc.id());
@@ -115,6 +123,14 @@ impl Chat {
         if text.starts_with("/") {
             match text.as_str() {
                 "/clear" => self.state_mut(cx.editor).history.clear(),
+                "/project on" => self.state_mut(cx.editor).set_project_context_enabled(true),
+                "/project off" => self.state_mut(cx.editor).set_project_context_enabled(false),
+                "/file" | "/add" => self.open_file_context_picker(cx),
+                "/attach" => self.open_attachment_picker(cx),
+                "/context" => self.list_context(cx.editor),
+                _ if text.starts_with("/drop ") => {
+                    self.drop_context(cx.editor, text.trim_start_matches("/drop ").trim())
+                }
                 _ => cx.editor.set_error("unknown cmd"),
             }
             return;
@@ -122,19 +138,21 @@ impl Chat {
 
         let is_quick = self.is_quick;
         let state = self.state_mut(cx.editor);
-        state.history.push(Message {
-            content: text.to_owned(),
-            role: "user".to_owned(),
-        });
+        let attachments = state.take_pending_attachments();
+        let mut message = Message::new("user", text.to_owned());
+        message.attachments = attachments;
+        state.history.push(message);
         let (send, recv) = tokio::sync::mpsc::channel(1024);
         state.in_progress = Some(chat::chat_state::InProgressState {
             channel: recv,
             ticks: 0,
+            stream: Default::default(),
         });
+        let is_copilot = state.client.is_copilot();
         let prompt = if is_quick {
-            chat::config::prompts::quick_copilot_instructions()
+            chat::config::prompts::quick_chat_instructions(is_copilot)
         } else {
-            chat::config::prompts::copilot_instructions()
+            chat::config::prompts::chat_instructions(is_copilot)
         };
         let state = self.state(&cx.editor);
         let lines = match chat::prompt::format_prompt(
@@ -151,22 +169,22 @@ impl Chat {
             }
         };
         let state = self.state_mut(cx.editor);
-        state.history.push(Message {
-            content: "".to_owned(),
-            role: "system".to_owned(),
-        });
+        state.history.push(Message::new("system", ""));
         let client = state.client.clone();
         cx.jobs.callback(async move {
             log::info!("start callback");
-            client
-                .send_chat(&lines, |text| async {
+            let callback: chat::client::ChatCallback = Box::new(move |text| {
+                let send = send.clone();
+                async move {
                     if send.send(text).await.is_err() {
                         return false;
                     }
                     request_redraw();
                     true
-                })
-                .await;
+                }
+                .boxed()
+            });
+            let result = client.send_chat(&lines, callback).await;
 
             Ok(Callback::EditorCompositor(Box::new(
                 move |editor, composor| {
@@ -187,38 +205,287 @@ impl Chat {
                     // make sure to empty the buffer
                     chat_window.state_mut(editor).fetch_inprogress();
                     chat_window.state_mut(editor).in_progress = None;
-                    editor.set_status("finished ai response");
+
+                    match result {
+                        Ok(()) => editor.set_status("finished ai response"),
+                        Err(err) => editor.set_error(format!("chat request failed: {err:#}")),
+                    }
+
+                    chat_window.attach_requested_context(editor);
 
                     if chat_window.is_quick {
-                        chat_window.apply_last_change(editor);
+                        chat_window.begin_review(editor);
                     }
                 },
             )))
         })
     }
 
-    fn apply_last_change(&mut self, editor: &mut Editor) {
-        let new_texts = self.state(editor).get_last_code_changes();
-        if new_texts.len() == 0 {
-            editor.set_error("no ai code block found");
+    /// Lists the context currently attached to this chat, one line per
+    /// entry, using each attached item's [`ContextProvider`](chat::context::ContextProvider)
+    /// name and description, plus its instance-specific detail (e.g. a
+    /// `#file` entry's path) so entries of the same kind are distinguishable
+    /// and the user can see what `/drop <n>` refers to.
+    fn list_context(&mut self, editor: &mut Editor) {
+        let context = &self.state(editor).context;
+        let listing = if context.is_empty() {
+            "no context attached".to_owned()
+        } else {
+            context
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let provider = item.provider();
+                    match item.detail(editor) {
+                        Some(detail) => {
+                            format!("{i}. #{} (`{detail}`) - {}", provider.name, provider.description)
+                        }
+                        None => format!("{i}. #{} - {}", provider.name, provider.description),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        self.state_mut(editor).history.push(Message::new("system", listing));
+    }
+
+    /// Removes the context entry at index `n` (as shown by `/context`).
+    fn drop_context(&mut self, editor: &mut Editor, n: &str) {
+        let Ok(index) = n.parse::<usize>() else {
+            editor.set_error("usage: /drop <n>");
+            return;
+        };
+        let state = self.state_mut(editor);
+        if index >= state.context.len() {
+            editor.set_error(format!("no context at index {index}"));
             return;
         }
-        let (path, start_line, end_line, new_text) = &new_texts[0];
-        let mut path = Path::new(path).to_owned();
+        state.context.remove(index);
+    }
+
+    /// Attaches whatever `#git`/`#buffers`/`#system` context the assistant
+    /// asked for in its last message (the `> #<command>:`<input>`` lines
+    /// `HELP_MSG` tells the model to emit), so it's resolved and included
+    /// on the user's next turn.
+    fn attach_requested_context(&mut self, editor: &mut Editor) {
+        let state = self.state(editor);
+        let Some(last) = state.history.last() else {
+            return;
+        };
+        let requested = chat::chat_state::parse_context_requests(&last.content);
+        if requested.is_empty() {
+            return;
+        }
+        self.state_mut(editor).context.extend(requested);
+    }
+
+    /// Opens Helix's fuzzy file picker over the workspace, pushing a
+    /// [`chat::context::Context::File`] entry for whichever file the user
+    /// selects. Doesn't open the file for editing: `FILE_CONTEXT`'s
+    /// resolver reads unopened files straight from disk, so doing so would
+    /// only be an unwanted side effect of attaching context.
+    fn open_file_context_picker(&self, cx: &mut Context) {
+        let root = find_workspace().0;
+        let files: Vec<PathBuf> = ignore::WalkBuilder::new(&root)
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+            .map(|entry| entry.into_path())
+            .collect();
+
+        let is_quick = self.is_quick;
+        let picker = Picker::new(files, root, move |cx: &mut Context, path: &PathBuf, _action| {
+            let path = path.to_string_lossy().into_owned();
+            cx.jobs.callback(async move {
+                Ok(Callback::EditorCompositor(Box::new(move |editor, composor| {
+                    let chat_window = if is_quick {
+                        composor.find_id::<Popup<Chat>>("aichat").map(Popup::contents_mut)
+                    } else {
+                        composor
+                            .find_id::<Overlay<Chat>>("aichat")
+                            .map(|layer| &mut layer.content)
+                    };
+                    let Some(chat_window) = chat_window else {
+                        log::error!("no chat window found");
+                        return;
+                    };
+                    chat_window
+                        .state_mut(editor)
+                        .context
+                        .push(chat::context::Context::File { path });
+                })))
+            });
+        });
+
+        cx.push_layer(Box::new(picker));
+    }
+
+    /// Opens Helix's fuzzy file picker over the workspace, pushing an
+    /// [`chat::chat_state::Attachment`] for whichever file the user selects
+    /// onto [`ChatState::pending_attachments`], to be folded into the next
+    /// message the user sends.
+    fn open_attachment_picker(&self, cx: &mut Context) {
         let root = find_workspace().0;
-        if path.is_relative() {
-            path = root.join(path)
+        let files: Vec<PathBuf> = ignore::WalkBuilder::new(&root)
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+            .map(|entry| entry.into_path())
+            .collect();
+
+        let is_quick = self.is_quick;
+        let picker = Picker::new(files, root, move |cx: &mut Context, path: &PathBuf, _action| {
+            let path = path.clone();
+            cx.jobs.callback(async move {
+                Ok(Callback::EditorCompositor(Box::new(move |editor, composor| {
+                    let chat_window = if is_quick {
+                        composor.find_id::<Popup<Chat>>("aichat").map(Popup::contents_mut)
+                    } else {
+                        composor
+                            .find_id::<Overlay<Chat>>("aichat")
+                            .map(|layer| &mut layer.content)
+                    };
+                    let Some(chat_window) = chat_window else {
+                        log::error!("no chat window found");
+                        return;
+                    };
+                    chat_window
+                        .state_mut(editor)
+                        .pending_attachments
+                        .push(chat::chat_state::Attachment::new(path));
+                })))
+            });
+        });
+
+        cx.push_layer(Box::new(picker));
+    }
+
+    /// Starts a review of the code changes parsed out of the last assistant
+    /// message: each hunk's diff is shown in the chat history in turn, and
+    /// `ctrl-y`/`ctrl-n` (see [`Self::review_accept`]/[`Self::review_reject`])
+    /// accept or reject it without touching any document until the review
+    /// finishes.
+    fn begin_review(&mut self, editor: &mut Editor) {
+        let changes = self.state(editor).get_last_code_changes();
+        if changes.is_empty() {
+            editor.set_error("no ai code block found");
+            return;
+        }
+
+        let state = self.state_mut(editor);
+        state.pending_changes = Some(PendingReview::new(changes));
+        self.show_current_hunk(editor);
+    }
+
+    /// Pushes a diff preview of the hunk the review is currently paused on
+    /// into the chat history, or a summary message once the review is done.
+    fn show_current_hunk(&mut self, editor: &mut Editor) {
+        let state = self.state(editor);
+        let Some(review) = &state.pending_changes else {
+            return;
+        };
+
+        if let Some(change) = review.current() {
+            let diff = render_diff_preview(editor, std::slice::from_ref(change));
+            let index = review.cursor + 1;
+            let total = review.changes.len();
+            let message = format!(
+                "{diff}\n\nhunk {index}/{total} - ctrl-y to accept, ctrl-n to reject"
+            );
+            self.state_mut(editor).history.push(Message::new("system", message));
+        }
+    }
+
+    /// Accepts the hunk currently under review (or, when no review is in
+    /// progress, starts one). Once every hunk has been decided, applies the
+    /// accepted hunks and clears the review.
+    fn review_accept(&mut self, editor: &mut Editor) {
+        if self.state(editor).pending_changes.is_none() {
+            self.begin_review(editor);
+            return;
+        }
+
+        let state = self.state_mut(editor);
+        let review = state.pending_changes.as_mut().unwrap();
+        review.accept_current();
+        self.finish_review_or_advance(editor);
+    }
+
+    /// Rejects the hunk currently under review. A no-op when no review is in
+    /// progress, since rejection only makes sense mid-review.
+    fn review_reject(&mut self, editor: &mut Editor) {
+        let Some(review) = self.state_mut(editor).pending_changes.as_mut() else {
+            return;
+        };
+        review.reject_current();
+        self.finish_review_or_advance(editor);
+    }
+
+    /// After a hunk has been decided, either shows the next one or, if that
+    /// was the last hunk, applies everything accepted and ends the review.
+    fn finish_review_or_advance(&mut self, editor: &mut Editor) {
+        let state = self.state(editor);
+        let Some(review) = &state.pending_changes else {
+            return;
+        };
+
+        if !review.is_done() {
+            self.show_current_hunk(editor);
+            return;
+        }
+
+        let review = self.state_mut(editor).pending_changes.take().unwrap();
+        let accepted = review.accepted.len();
+        let rejected = review.changes.len() - accepted;
+        if accepted > 0 {
+            self.apply_changes(editor, review.accepted);
+        }
+        editor.set_status(format!("applied {accepted} hunk(s), rejected {rejected}"));
+    }
+
+    /// Applies every change, grouped by resolved document path, opening the
+    /// file first if the user doesn't already have it open. Within a file,
+    /// edits are applied from the bottom up so that applying one doesn't
+    /// shift the line numbers of the ones still to come.
+    fn apply_changes(&mut self, editor: &mut Editor, changes: Vec<(String, usize, usize, String)>) {
+        let mut by_path: std::collections::HashMap<String, Vec<(usize, usize, String)>> =
+            std::collections::HashMap::new();
+        for (path, start_line, end_line, new_text) in changes {
+            by_path
+                .entry(path)
+                .or_default()
+                .push((start_line, end_line, new_text));
+        }
+
+        for (path, mut edits) in by_path {
+            edits.sort_by(|a, b| b.0.cmp(&a.0));
+
+            let resolved = resolve_path(&path);
+            let doc_id = match editor.document_by_path(&resolved) {
+                Some(doc) => doc.id(),
+                None => match editor.open(&resolved, Action::Load) {
+                    Ok(doc_id) => doc_id,
+                    Err(err) => {
+                        editor.set_error(format!("failed to open {path}: {err}"));
+                        continue;
+                    }
+                },
+            };
+            let Some(doc) = editor.document_mut(doc_id) else {
+                continue;
+            };
+
+            for (start_line, end_line, new_text) in edits {
+                let (start, end) =
+                    clamped_line_range_to_chars(doc.text().slice(..), start_line, end_line);
+
+                let transaction = Transaction::change(
+                    doc.text(),
+                    [(start, end, Some(new_text.into()))].into_iter(),
+                );
+                doc.apply(&transaction, self.view_id);
+            }
         }
-        let doc = editor.document_by_path_mut(path).unwrap();
-        let end_line = (*end_line).min(doc.text().len_lines() - 1);
-        let start = doc.text().line_to_char(*start_line);
-        let end = doc.text().line_to_char(end_line) + doc.text().line(end_line).len_chars() - 1;
-
-        let transaction = Transaction::change(
-            doc.text(),
-            [(start, end, Some(new_text.into()))].into_iter(),
-        );
-        doc.apply(&transaction, self.view_id);
     }
 
     fn render_picker(&mut self, area: Rect, surface: &mut Surface, cx: &mut Context) {
@@ -249,7 +516,14 @@ impl Chat {
         if let Some(progress) = &self.state(&cx.editor).in_progress {
             let frames = ["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"];
             let frame = frames[progress.ticks % frames.len()];
-            surface.set_string(line_area.x, line_area.y + 1, frame, Style::default());
+            let indicator = match &progress.stream.error {
+                Some(err) => format!("{frame} {err}"),
+                None if progress.stream.detected > 0 => {
+                    format!("{frame} {} pending edit(s)", progress.stream.detected)
+                }
+                None => frame.to_owned(),
+            };
+            surface.set_string(line_area.x, line_area.y + 1, indicator, Style::default());
         } else {
             // render the prompt first since it will clear its background
             self.prompt.render(line_area, surface, cx);
@@ -396,8 +670,14 @@ impl Component for Chat {
             EventResult::Consumed(Some(callback))
         };
 
+        // While a hunk review is in progress `ctrl-n` rejects the current
+        // hunk instead of scrolling, since there's nothing else to review
+        // until the user decides on it.
+        let reviewing = self.state(&ctx.editor).pending_changes.is_some();
+
         match key_event {
-            ctrl!('y') => self.apply_last_change(ctx.editor),
+            ctrl!('y') => self.review_accept(ctx.editor),
+            ctrl!('n') if reviewing => self.review_reject(ctx.editor),
             shift!(Tab) | key!(Up) | ctrl!('p') => {
                 self.move_by(1, Direction::Backward);
             }
@@ -474,3 +754,111 @@ impl Component for Chat {
         Some("aichat")
     }
 }
+
+/// Converts a `start_line..=end_line` pair (0-based, inclusive, as parsed
+/// from an AI code-change header) into a char range within `text`.
+///
+/// The header is the model's own invention and routinely hallucinates line
+/// numbers past EOF, or `start_line > end_line`, so both are clamped into
+/// the document here rather than trusted. `end_line` landing on a trailing
+/// empty line (the normal case for any document that ends with a newline)
+/// is handled too: such a line has zero chars, so the range must not
+/// subtract one from its start to find an "end" char.
+fn clamped_line_range_to_chars(
+    text: helix_core::RopeSlice,
+    start_line: usize,
+    end_line: usize,
+) -> (usize, usize) {
+    let last_line = text.len_lines() - 1;
+    let end_line = end_line.min(last_line);
+    let start_line = start_line.min(end_line);
+
+    let start = text.line_to_char(start_line);
+    let line_len = text.line(end_line).len_chars();
+    let end = text.line_to_char(end_line) + line_len.saturating_sub(1);
+
+    (start, end)
+}
+
+/// Resolves a path from an AI code change header against the workspace
+/// root, the same way context providers resolve file paths for display.
+fn resolve_path(path: &str) -> std::path::PathBuf {
+    let path = Path::new(path);
+    if path.is_relative() {
+        find_workspace().0.join(path)
+    } else {
+        path.to_owned()
+    }
+}
+
+/// Renders a unified-diff-style preview of `changes` against the current
+/// contents of their documents, for display in the chat history before the
+/// user confirms applying them.
+fn render_diff_preview(editor: &Editor, changes: &[(String, usize, usize, String)]) -> String {
+    changes
+        .iter()
+        .map(|(path, start_line, end_line, new_text)| {
+            let old_lines: Vec<String> = match editor.document_by_path(resolve_path(path)) {
+                Some(doc) => {
+                    let end_line = (*end_line).min(doc.text().len_lines().saturating_sub(1));
+                    (*start_line..=end_line)
+                        .map(|line| doc.text().line(line).to_string())
+                        .collect()
+                }
+                None => Vec::new(),
+            };
+
+            let mut block = format!("--- {path}\n");
+            for line in &old_lines {
+                block.push('-');
+                block.push_str(line.trim_end_matches('\n'));
+                block.push('\n');
+            }
+            for line in new_text.lines() {
+                block.push('+');
+                block.push_str(line);
+                block.push('\n');
+            }
+            format!("```diff\n{block}```")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use helix_core::Rope;
+
+    #[test]
+    fn test_clamped_line_range_to_chars_trailing_newline() {
+        // Every saved file ending with a newline has an empty trailing
+        // line; end_line landing there must not underflow.
+        let text = Rope::from("one\ntwo\nthree\n");
+        let last_line = text.len_lines() - 1;
+
+        let (start, end) = clamped_line_range_to_chars(text.slice(..), 2, last_line);
+        assert_eq!(start, text.line_to_char(2));
+        assert_eq!(end, text.line_to_char(last_line));
+    }
+
+    #[test]
+    fn test_clamped_line_range_to_chars_past_eof() {
+        let text = Rope::from("one\ntwo\n");
+
+        let (start, end) = clamped_line_range_to_chars(text.slice(..), 9999, 10000);
+        let last_line = text.len_lines() - 1;
+        assert_eq!(start, text.line_to_char(last_line));
+        assert_eq!(end, text.line_to_char(last_line));
+    }
+
+    #[test]
+    fn test_clamped_line_range_to_chars_reversed() {
+        let text = Rope::from("one\ntwo\nthree\n");
+
+        let (start, end) = clamped_line_range_to_chars(text.slice(..), 2, 0);
+        // start_line > end_line clamps start down to end.
+        assert_eq!(start, text.line_to_char(0));
+        assert_eq!(end, text.line_to_char(0) + text.line(0).len_chars() - 1);
+    }
+}
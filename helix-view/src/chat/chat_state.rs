@@ -1,26 +1,241 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use base64::Engine as _;
 use helix_core::regex::Regex;
-use serde::Serialize;
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
 use tokio::sync::mpsc::Receiver;
 
-use super::{client::ChatClient, context::Context};
+use super::{client::ChatClient, config::ChatConfig, context::Context};
+
+/// How an [`Attachment`]'s bytes should be folded into a chat request,
+/// decided by [`sniff_payload`] from the file's content rather than its
+/// extension.
+pub enum AttachmentPayload {
+    /// An image or PDF, ready to embed as a `data:` URI.
+    DataUri { mime_type: String, data: String },
+    /// Anything else, inlined as fenced text with a filename header so
+    /// text-only models still see the content.
+    Text(String),
+}
+
+/// A local file referenced by a [`Message`], e.g. an image or PDF the user
+/// wants to attach to a prompt for a vision-capable model.
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub path: PathBuf,
+}
+
+impl Attachment {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Reads the file and decides how to fold it into a chat request. The
+    /// decision is made by sniffing the file's content (via `infer`)
+    /// instead of trusting its extension.
+    pub fn read(&self) -> Result<AttachmentPayload, anyhow::Error> {
+        let bytes = std::fs::read(&self.path)
+            .with_context(|| format!("failed to read attachment {}", self.path.display()))?;
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(sniff_payload(&name, &bytes))
+    }
+}
+
+/// Sniffs `bytes` to decide whether they should be embedded as a `data:`
+/// URI (images and PDFs) or inlined as fenced text (everything else,
+/// including files `infer` doesn't recognize).
+fn sniff_payload(name: &str, bytes: &[u8]) -> AttachmentPayload {
+    let kind = infer::get(bytes).filter(|kind| {
+        kind.mime_type().starts_with("image/") || kind.mime_type() == "application/pdf"
+    });
+
+    match kind {
+        Some(kind) => AttachmentPayload::DataUri {
+            mime_type: kind.mime_type().to_owned(),
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+        },
+        None => {
+            let text = String::from_utf8_lossy(bytes);
+            AttachmentPayload::Text(format!("# FILE:{name}\n```\n{text}\n```\n"))
+        }
+    }
+}
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Message {
     pub content: String,
     pub role: String,
+    pub attachments: Vec<Attachment>,
+}
+
+impl Message {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            role: role.into(),
+            attachments: Vec::new(),
+        }
+    }
 }
 
 pub struct InProgressState {
     pub channel: Receiver<String>,
     pub ticks: usize,
+    /// Tracks fenced code-change blocks as tokens stream in, so the UI can
+    /// show a pending-edit indicator before the response finishes.
+    pub stream: StreamBlockTracker,
 }
 
+/// Incrementally recognises `[file:...] line:a-b` code-change blocks as
+/// assistant tokens arrive, mirroring what [`parse_code_changes`] does once
+/// a message is complete, so the chat UI can show a live count of detected
+/// blocks (and surface a malformed header right away) while still
+/// streaming.
 #[derive(Default)]
+pub struct StreamBlockTracker {
+    /// Text accumulated since the last newline; a header or fence marker is
+    /// only recognised once its line is complete.
+    line_buf: String,
+    /// Set once a `[file:...] line:a-b` header has been seen and is waiting
+    /// for the code fence that should immediately follow it.
+    pending_header: bool,
+    in_fence: bool,
+    /// Number of complete header+fence pairs seen so far in this message.
+    pub detected: usize,
+    /// Set as soon as a `[file:...]` header's `line:` suffix fails to parse.
+    pub error: Option<String>,
+}
+
+impl StreamBlockTracker {
+    /// Feeds a newly-arrived chunk of streamed text through the tracker.
+    pub fn push(&mut self, token: &str) {
+        for ch in token.chars() {
+            if ch == '\n' {
+                self.end_line();
+            } else {
+                self.line_buf.push(ch);
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = std::mem::take(&mut self.line_buf);
+        let trimmed = line.trim();
+
+        if !self.in_fence && trimmed.starts_with("[file:") && trimmed.contains("line:") {
+            if line_range_regex().is_match(trimmed) {
+                self.pending_header = true;
+            } else {
+                self.error = Some(format!("malformed code-change header: {trimmed}"));
+            }
+        } else if trimmed.starts_with("```") {
+            if self.in_fence {
+                self.in_fence = false;
+                if self.pending_header {
+                    self.pending_header = false;
+                    self.detected += 1;
+                }
+            } else {
+                self.in_fence = true;
+            }
+        } else if !trimmed.is_empty() && !self.in_fence {
+            // A header with no fence immediately after it is dropped, same
+            // as the batch parser: a stray paragraph clears it.
+            self.pending_header = false;
+        }
+    }
+}
+
+/// A single `[file:...](path) line:start-end` block paired with the code
+/// fence that immediately follows it, as emitted by `COMMON_BASE`.
+///
+/// `start_line`/`end_line` are 0-based and inclusive, already converted from
+/// the model's 1-based range.
+pub type CodeChange = (String, usize, usize, String);
+
+/// Code changes parsed from the last assistant message, awaiting per-hunk
+/// accept/reject from the user before any of them touch a document.
+///
+/// Kept on [`ChatState`] (rather than as transient UI state) so the review
+/// survives redraws: the user can page through the chat history, resize the
+/// terminal, etc. without losing their place partway through a review.
+#[derive(Default)]
+pub struct PendingReview {
+    pub changes: Vec<CodeChange>,
+    /// Index into `changes` of the hunk currently awaiting a decision.
+    pub cursor: usize,
+    /// Hunks the user has accepted so far, in review order.
+    pub accepted: Vec<CodeChange>,
+}
+
+impl PendingReview {
+    pub fn new(changes: Vec<CodeChange>) -> Self {
+        Self {
+            changes,
+            cursor: 0,
+            accepted: Vec::new(),
+        }
+    }
+
+    /// The hunk currently awaiting a decision, or `None` once every hunk has
+    /// been accepted or rejected.
+    pub fn current(&self) -> Option<&CodeChange> {
+        self.changes.get(self.cursor)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.cursor >= self.changes.len()
+    }
+
+    /// Accepts the current hunk and advances to the next one.
+    pub fn accept_current(&mut self) {
+        if let Some(change) = self.changes.get(self.cursor).cloned() {
+            self.accepted.push(change);
+        }
+        self.cursor += 1;
+    }
+
+    /// Rejects the current hunk and advances to the next one.
+    pub fn reject_current(&mut self) {
+        self.cursor += 1;
+    }
+}
+
 pub struct ChatState {
     pub history: Vec<Message>,
     pub context: Vec<Context>,
     pub in_progress: Option<InProgressState>,
     pub client: ChatClient,
+    /// Code changes parsed from the last assistant message, awaiting user
+    /// confirmation before being applied to their documents.
+    pub pending_changes: Option<PendingReview>,
+    /// Files picked with `/attach`, waiting to be folded into the next
+    /// message the user sends (see [`Self::take_pending_attachments`]).
+    pub pending_attachments: Vec<Attachment>,
+}
+
+impl Default for ChatState {
+    /// Builds the client from the user's `chat.toml` (falling back to
+    /// Copilot when it's absent or doesn't configure a provider) rather
+    /// than always defaulting to Copilot.
+    fn default() -> Self {
+        let config = ChatConfig::load();
+        super::context::set_system_context_enabled(config.enable_system_context);
+
+        Self {
+            history: Vec::new(),
+            context: Vec::new(),
+            in_progress: None,
+            client: ChatClient::from_config(&config),
+            pending_changes: None,
+            pending_attachments: Vec::new(),
+        }
+    }
 }
 
 impl ChatState {
@@ -29,26 +244,216 @@ impl ChatState {
             let last = self.history.last_mut().unwrap();
             while let Ok(res) = recv.channel.try_recv() {
                 log::info!("new copilot text: {res:?}");
+                recv.stream.push(&res);
                 last.content.push_str(&res);
                 recv.ticks += 1;
             }
         }
     }
-    pub fn get_last_code_changes(&self) -> Vec<(String, usize, usize, String)> {
+
+    pub fn get_last_code_changes(&self) -> Vec<CodeChange> {
         let last_msg = self.history.last().unwrap().clone();
         log::info!("finding code block: {:?}", last_msg.content);
-        // [file:test.py](test.py) line:1-5\n\n```python\ndef test():\n    \"\"\"\n    A simple test function that initializes a variable `x` to 1.\n    \"\"\"\n    x = 1\n    pass\n```
-        let code_regex =
-            Regex::new(r"(?ms)^\[file:([^\]]+)\]\([^)]+\) line:(\d+)-(\d+)\n+```\w+\n(.*)\n```")
-                .unwrap();
-
-        let mut changes = Vec::new();
-        if let Some(m) = code_regex.captures(&last_msg.content) {
-            let (_, [filename, line_start, line_end, code]) = m.extract();
-            let line_start = line_start.parse::<usize>().unwrap().saturating_sub(1);
-            let line_end = line_end.parse::<usize>().unwrap().saturating_sub(1);
-            changes.push((filename.to_owned(), line_start, line_end, code.to_owned()));
-        }
-        changes
+        parse_code_changes(&last_msg.content)
+    }
+
+    /// Drains the files picked via `/attach` since the last message was
+    /// sent, for attaching to the message currently being submitted.
+    pub fn take_pending_attachments(&mut self) -> Vec<Attachment> {
+        std::mem::take(&mut self.pending_attachments)
+    }
+
+    /// Toggles the ambient `#project` context on or off, e.g. from the
+    /// `/project on|off` slash command. Idempotent: enabling it twice does
+    /// not add it twice, and disabling it when absent is a no-op.
+    pub fn set_project_context_enabled(&mut self, enabled: bool) {
+        self.context.retain(|item| !item.is_project());
+        if enabled {
+            self.context.push(Context::Project);
+        }
+    }
+}
+
+/// Matches the `line:<start>-<end>` suffix of a `[file:...](path)` header,
+/// e.g. `line:1-5`.
+fn line_range_regex() -> &'static Regex {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"line:(\d+)-(\d+)\s*$").unwrap())
+}
+
+/// Walks `content` as markdown and pairs each `[file:<name>](<path>)
+/// line:<start>-<end>` header with the fenced code block immediately
+/// following it, producing `(path, start_line, end_line, replacement)`
+/// tuples with 0-based inclusive line ranges.
+///
+/// A header with no following code fence is dropped rather than paired with
+/// an unrelated later block, since a plain paragraph (or a second header)
+/// between them clears the pending header before a fence can close it.
+pub fn parse_code_changes(content: &str) -> Vec<CodeChange> {
+    let mut changes = Vec::new();
+    let mut pending_header: Option<(String, usize, usize)> = None;
+    let mut link_dest: Option<String> = None;
+    let mut paragraph_text = String::new();
+    let mut in_code_block = false;
+    let mut code_text = String::new();
+
+    for event in Parser::new(content) {
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                link_dest = None;
+                paragraph_text.clear();
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                link_dest = Some(dest_url.into_string());
+            }
+            Event::End(TagEnd::Paragraph) => {
+                pending_header = link_dest.take().and_then(|path| {
+                    let caps = line_range_regex().captures(paragraph_text.trim())?;
+                    let start = caps[1].parse::<usize>().ok()?.saturating_sub(1);
+                    let end = caps[2].parse::<usize>().ok()?.saturating_sub(1);
+                    Some((path, start, end))
+                });
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                code_text.clear();
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                if let Some((path, start, end)) = pending_header.take() {
+                    let replacement = code_text.trim_end_matches('\n').to_owned();
+                    changes.push((path, start, end, replacement));
+                }
+            }
+            Event::Text(text) => {
+                if in_code_block {
+                    code_text.push_str(&text);
+                } else {
+                    paragraph_text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    changes
+}
+
+/// Matches a context request line, e.g. `` > #git:`staged` ``, in the
+/// format `HELP_MSG` (see [`super::prompt`]) tells the model to use when it
+/// needs more context than it was given.
+fn context_request_regex() -> &'static Regex {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?m)^>\s*#(\w+):`([^`]*)`\s*$").unwrap())
+}
+
+/// Parses every `` > #<command>:`<input>` `` line out of an assistant
+/// message into the [`Context`] variant it requests, so it can be attached
+/// ahead of the user's next turn.
+///
+/// `#file` isn't handled here: the UI already has a dedicated `/file`
+/// fuzzy picker for attaching files, so a model-requested `#file` is left
+/// for the user to fulfil that way rather than trusting a path straight
+/// out of the model's own output. Unrecognized commands are ignored.
+pub fn parse_context_requests(content: &str) -> Vec<Context> {
+    context_request_regex()
+        .captures_iter(content)
+        .filter_map(|caps| {
+            let command = caps.get(1)?.as_str();
+            let arg = caps.get(2)?.as_str().to_owned();
+            match command {
+                "git" => Some(Context::Git { arg }),
+                "buffers" => Some(Context::Buffers { arg }),
+                "system" => Some(Context::System { command: arg }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_code_changes_single_block() {
+        let content = "[file:test.py](test.py) line:1-5\n\n```python\ndef test():\n    x = 1\n```";
+        let changes = parse_code_changes(content);
+        assert_eq!(
+            changes,
+            vec![(
+                "test.py".to_owned(),
+                0,
+                4,
+                "def test():\n    x = 1".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_code_changes_multiple_blocks() {
+        let content = "[file:a.rs](a.rs) line:1-1\n\n```rust\nfn a() {}\n```\n\n[file:b.rs](b.rs) line:2-2\n\n```rust\nfn b() {}\n```";
+        let changes = parse_code_changes(content);
+        assert_eq!(
+            changes,
+            vec![
+                ("a.rs".to_owned(), 0, 0, "fn a() {}".to_owned()),
+                ("b.rs".to_owned(), 1, 1, "fn b() {}".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sniff_payload_detects_image_by_content() {
+        let png_header = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+        match sniff_payload("screenshot.png", &png_header) {
+            AttachmentPayload::DataUri { mime_type, .. } => assert_eq!(mime_type, "image/png"),
+            AttachmentPayload::Text(_) => panic!("expected a data URI payload"),
+        }
+    }
+
+    #[test]
+    fn test_sniff_payload_falls_back_to_text() {
+        match sniff_payload("notes.txt", b"hello world") {
+            AttachmentPayload::Text(text) => {
+                assert_eq!(text, "# FILE:notes.txt\n```\nhello world\n```\n")
+            }
+            AttachmentPayload::DataUri { .. } => panic!("expected a text payload"),
+        }
+    }
+
+    #[test]
+    fn test_parse_code_changes_header_without_fence_is_skipped() {
+        let content = "[file:a.rs](a.rs) line:1-1\n\nNever mind.\n\n```rust\nfn a() {}\n```";
+        let changes = parse_code_changes(content);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_stream_block_tracker_detects_block_as_it_streams() {
+        let mut tracker = StreamBlockTracker::default();
+        for token in ["[file:a.rs](a.rs) line:1-1\n", "\n```rust\n", "fn a() {}\n", "```\n"] {
+            tracker.push(token);
+        }
+        assert_eq!(tracker.detected, 1);
+        assert!(tracker.error.is_none());
+    }
+
+    #[test]
+    fn test_stream_block_tracker_flags_malformed_header_immediately() {
+        let mut tracker = StreamBlockTracker::default();
+        tracker.push("[file:a.rs](a.rs) line:oops\n");
+        assert_eq!(tracker.detected, 0);
+        assert!(tracker.error.is_some());
+    }
+
+    #[test]
+    fn test_parse_context_requests() {
+        let content = "Sure, I'll need more to go on.\n\n> #git:`staged`\n> #system:`uname -a`\n> #buffers:`visible`\n> #unknown:`whatever`\n";
+        let requests = parse_context_requests(content);
+        assert_eq!(requests.len(), 3);
+        assert!(matches!(&requests[0], Context::Git { arg } if arg == "staged"));
+        assert!(matches!(&requests[1], Context::System { command } if command == "uname -a"));
+        assert!(matches!(&requests[2], Context::Buffers { arg } if arg == "visible"));
     }
 }
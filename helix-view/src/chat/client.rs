@@ -1,38 +1,291 @@
-use std::{process::Stdio, sync::Arc};
+use std::sync::Arc;
 
-use anyhow::bail;
+use anyhow::{bail, Context as _};
 use arc_swap::ArcSwapOption;
-use futures_util::Future;
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
 use helix_loader::config_dir;
 use serde::{Deserialize, Serialize};
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    process::ChildStdin,
-};
 
-use super::chat_state::Message;
+use super::chat_state::{AttachmentPayload, Message};
+use super::config::provider::{AuthScheme, ProviderConfig};
+use super::config::ChatConfig;
+
+/// Callback invoked with each streamed chunk of assistant text.
+///
+/// Returning `false` stops the stream early, e.g. because the user
+/// cancelled or the UI wants to apply backpressure.
+pub type ChatCallback = Box<dyn FnMut(String) -> BoxFuture<'static, bool> + Send>;
 
 #[derive(Serialize)]
 struct ChatRequest {
     n: u32,
-    top_p: u32,
+    top_p: f32,
     stream: bool,
     temperature: f32,
     model: String,
-    messages: Vec<Message>,
+    messages: Vec<WireMessage>,
 }
 
-struct ChatClientData {
-    endpoint: String,
-    token: String,
+/// A chat completion message in the shape providers expect on the wire.
+///
+/// Unlike [`Message`], `content` here is either a plain string or an array
+/// of content parts, matching the OpenAI-compatible multimodal format.
+#[derive(Serialize, Debug)]
+struct WireMessage {
+    role: String,
+    content: MessageContent,
 }
 
-#[derive(Clone, Default)]
-pub struct ChatClient {
-    data: Arc<ArcSwapOption<ChatClientData>>,
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
 }
 
-impl ChatClientData {
+#[derive(Serialize, Debug)]
+struct ImageUrl {
+    url: String,
+}
+
+/// Converts chat history into the wire format, folding each message's
+/// attachments into multimodal content parts. A message with no
+/// attachments keeps the plain-string `content` shape most providers
+/// expect for ordinary text turns.
+fn to_wire_messages(messages: &[Message]) -> Result<Vec<WireMessage>, anyhow::Error> {
+    messages
+        .iter()
+        .map(|message| {
+            let content = if message.attachments.is_empty() {
+                MessageContent::Text(message.content.clone())
+            } else {
+                let mut parts = vec![ContentPart::Text {
+                    text: message.content.clone(),
+                }];
+                for attachment in &message.attachments {
+                    parts.push(match attachment.read()? {
+                        AttachmentPayload::DataUri { mime_type, data } => ContentPart::ImageUrl {
+                            image_url: ImageUrl {
+                                url: format!("data:{mime_type};base64,{data}"),
+                            },
+                        },
+                        AttachmentPayload::Text(text) => ContentPart::Text { text },
+                    });
+                }
+                MessageContent::Parts(parts)
+            };
+            Ok(WireMessage {
+                role: message.role.clone(),
+                content,
+            })
+        })
+        .collect()
+}
+
+/// A backend capable of carrying out a chat completion request.
+///
+/// Implementations stream the assistant's reply incrementally through
+/// `callback` rather than returning the full text at once, so the UI can
+/// render tokens as they arrive.
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    async fn send_chat(
+        &self,
+        messages: &[Message],
+        callback: ChatCallback,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Whether this provider is GitHub Copilot, so callers can decide
+    /// whether Copilot-specific system prompt framing applies.
+    fn is_copilot(&self) -> bool {
+        false
+    }
+}
+
+/// Incrementally splits a byte stream into lines.
+///
+/// SSE events arrive as arbitrary-sized chunks over the wire, so a `data:`
+/// line is not guaranteed to land in a single chunk, and a multi-byte UTF-8
+/// character (any non-ASCII assistant text) isn't guaranteed to land whole
+/// in one chunk either. This buffers raw, undecoded bytes and only decodes
+/// once a line is complete, so a character split across chunks is never
+/// independently lossy-decoded on either side of the split.
+#[derive(Default)]
+struct LineSplitter {
+    buf: Vec<u8>,
+}
+
+impl LineSplitter {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(chunk);
+
+        let mut lines = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+            let line = self.buf.drain(..=pos).collect::<Vec<u8>>();
+            let line = String::from_utf8_lossy(&line).into_owned();
+            lines.push(line.trim_end_matches(['\r', '\n']).to_owned());
+        }
+        lines
+    }
+}
+
+/// Builds a log-safe stand-in for `messages`, replacing each attachment's
+/// `data:` URI with a placeholder noting its size.
+///
+/// Attachments can be multi-megabyte base64-encoded images or PDFs; logging
+/// them verbatim at `info` level would flood the log on every chat request.
+fn redact_attachments(messages: &[WireMessage]) -> Vec<WireMessage> {
+    messages
+        .iter()
+        .map(|message| {
+            let content = match &message.content {
+                MessageContent::Text(text) => MessageContent::Text(text.clone()),
+                MessageContent::Parts(parts) => MessageContent::Parts(
+                    parts
+                        .iter()
+                        .map(|part| match part {
+                            ContentPart::Text { text } => ContentPart::Text { text: text.clone() },
+                            ContentPart::ImageUrl { image_url } => ContentPart::ImageUrl {
+                                image_url: ImageUrl {
+                                    url: format!("<attachment omitted, {} bytes>", image_url.url.len()),
+                                },
+                            },
+                        })
+                        .collect(),
+                ),
+            };
+            WireMessage {
+                role: message.role.clone(),
+                content,
+            }
+        })
+        .collect()
+}
+
+/// POSTs `request` to `endpoint` with `headers` and streams the response
+/// body as SSE, forwarding each `data:` event's
+/// `choices[0].delta.content` to `callback`.
+async fn run_http_chat(
+    endpoint: &str,
+    headers: &[(String, String)],
+    request: &ChatRequest,
+    mut callback: ChatCallback,
+) -> Result<(), anyhow::Error> {
+    let client = reqwest::Client::new();
+    let mut builder = client
+        .post(endpoint)
+        .header("Content-Type", "application/json");
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+
+    log::info!(
+        "sending msgs to {endpoint}: {:?}",
+        redact_attachments(&request.messages)
+    );
+
+    let response = builder.json(request).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        bail!("chat request to {endpoint} failed with status {status}: {body}");
+    }
+
+    let mut body = response.bytes_stream();
+    let mut lines = LineSplitter::default();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.context("error reading chat response stream")?;
+        for line in lines.push(&chunk) {
+            if line == "data: [DONE]" {
+                return Ok(());
+            }
+            let line = line.strip_prefix("data:").unwrap_or(&line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(parsed) => {
+                    if let Some(content) = parsed["choices"]
+                        .get(0)
+                        .and_then(|choice| choice["delta"].get("content"))
+                    {
+                        if let Some(content_str) = content.as_str() {
+                            if !callback(content_str.to_owned()).await {
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+                Err(parse_err) => {
+                    log::error!("chat response is not valid json: {line:?} {parse_err:?}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends chat requests to a generic OpenAI-compatible endpoint, using the
+/// endpoint, auth scheme, model and sampling parameters from a
+/// [`ProviderConfig`]. Covers OpenAI, Azure OpenAI deployments, and local
+/// Ollama/llama.cpp servers.
+pub struct GenericProvider {
+    config: ProviderConfig,
+}
+
+impl GenericProvider {
+    pub fn new(config: ProviderConfig) -> Self {
+        Self { config }
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        match &self.config.auth {
+            AuthScheme::None => Vec::new(),
+            AuthScheme::Bearer { token } => {
+                vec![("Authorization".to_owned(), format!("Bearer {token}"))]
+            }
+            AuthScheme::Header { name, value } => vec![(name.clone(), value.clone())],
+        }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for GenericProvider {
+    async fn send_chat(
+        &self,
+        messages: &[Message],
+        callback: ChatCallback,
+    ) -> Result<(), anyhow::Error> {
+        let request = ChatRequest {
+            n: self.config.sampling.n,
+            top_p: self.config.sampling.top_p,
+            stream: true,
+            temperature: self.config.sampling.temperature,
+            model: self.config.model.clone(),
+            messages: to_wire_messages(messages)?,
+        };
+
+        run_http_chat(&self.config.endpoint, &self.headers(), &request, callback).await
+    }
+}
+
+struct CopilotTokenData {
+    endpoint: String,
+    token: String,
+}
+
+impl CopilotTokenData {
     pub async fn from_config() -> Result<Self, anyhow::Error> {
         #[derive(Deserialize)]
         struct GithubAppConfig {
@@ -69,19 +322,20 @@ impl ChatClientData {
             .ok_or_else(|| anyhow::anyhow!("No apps found in configuration"))?;
         let oauth_token = &app_config.oauth_token;
 
-        let child = tokio::process::Command::new("curl")
-            .arg("https://api.github.com/copilot_internal/v2/token")
-            .arg("-H")
-            .arg(format!("Authorization: Token {}", oauth_token))
-            .output()
+        let client = reqwest::Client::new();
+        let response = client
+            .get("https://api.github.com/copilot_internal/v2/token")
+            .header("Authorization", format!("Token {}", oauth_token))
+            .send()
             .await?;
 
-        if !child.status.success() {
-            bail!("could not fetch token from github {:?}", child.stdout);
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!("could not fetch token from github: {status} {body}");
         }
 
-        let token_resp: GithubTokenResp = serde_json::from_slice(&child.stdout)
-            .map_err(|e| anyhow::anyhow!("Failed to parse token response: {:?}", e))?;
+        let token_resp: GithubTokenResp = response.json().await?;
 
         let endpoint = token_resp.endpoints.api;
         let token = token_resp.token;
@@ -90,136 +344,134 @@ impl ChatClientData {
     }
 }
 
-impl ChatClient {
-    async fn get_or_init(&self) -> Arc<ChatClientData> {
+/// Sends chat requests to GitHub Copilot, fetching a short-lived API token
+/// via the existing OAuth app config on first use.
+#[derive(Clone, Default)]
+pub struct CopilotProvider {
+    data: Arc<ArcSwapOption<CopilotTokenData>>,
+}
+
+impl CopilotProvider {
+    async fn get_or_init(&self) -> Result<Arc<CopilotTokenData>, anyhow::Error> {
         if self.data.load().is_none() {
             self.data
-                .store(Some(Arc::new(ChatClientData::from_config().await.unwrap())))
+                .store(Some(Arc::new(CopilotTokenData::from_config().await?)))
         }
-        self.data.load_full().unwrap()
+        Ok(self.data.load_full().unwrap())
+    }
+}
+
+#[async_trait]
+impl ChatProvider for CopilotProvider {
+    fn is_copilot(&self) -> bool {
+        true
     }
 
-    pub async fn send_chat<F: Future<Output = bool>>(
+    async fn send_chat(
         &self,
-        message: &[Message],
-        mut callback: impl FnMut(String) -> F,
-    ) {
-        let config = self.get_or_init().await;
+        messages: &[Message],
+        callback: ChatCallback,
+    ) -> Result<(), anyhow::Error> {
+        let config = self.get_or_init().await?;
         log::info!(
             "start callback endpoint={} token={}",
             config.endpoint,
             config.token,
         );
-        let mut child = tokio::process::Command::new("curl")
-            .arg("--request")
-            .arg("POST")
-            .arg("--silent")
-            .arg(format!("{}/chat/completions", &config.endpoint))
-            .arg("-H")
-            .arg(format!("Authorization: Bearer {}", config.token))
-            .arg("-H")
-            .arg("x-ms-useragent: Helix/0.1.0")
-            .arg("-H")
-            .arg("x-ms-user-agent: Helix/0.1.0")
-            .arg("-H")
-            .arg("Copilot-Integration-Id: vscode-chat")
-            .arg("-H")
-            .arg("Content-Type: application/json")
-            .arg("--data")
-            .arg("@-")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .stdin(Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-            .unwrap();
-
-        log::info!("spawned curl {:?}", child.id());
-
-        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
-        let mut err_lines = BufReader::new(child.stderr.take().unwrap()).lines();
-        let mut inp_lines = child.stdin.take().unwrap();
-
-        log::info!("sending msgs curl: {message:?}");
 
         let request = ChatRequest {
             n: 1,
-            top_p: 1,
+            top_p: 1.0,
             stream: true,
             temperature: 0.1,
-            // model: "gpt-3.5-turbo".to_owned(),
             model: "gpt-4o-2024-08-06".to_owned(),
-            messages: message.to_owned(),
-        };
-        let history = serde_json::to_vec(&request).unwrap();
-        let send_result = async move {
-            inp_lines.write_all(&history).await?;
-            inp_lines.shutdown().await?; // Ensure stdin is properly closed
-            drop::<ChildStdin>(inp_lines);
-            log::info!("finish curl send");
-            Ok(())
+            messages: to_wire_messages(messages)?,
         };
 
-        let recv_result = async {
-            while let Some(line) = lines.next_line().await? {
-                // log::info!("curl raw result {:?}", line);
-                if line == "data: [DONE]" {
-                    break;
-                }
-                let line = line.strip_prefix("data:").unwrap_or(&line).trim();
-                if !line.is_empty() {
-                    // log::info!("curl processed result {:?}", line);
-                    match serde_json::from_str::<serde_json::Value>(line) {
-                        Ok(parsed) => {
-                            if let Some(content) = parsed["choices"]
-                                .get(0)
-                                .and_then(|choice| choice["delta"].get("content"))
-                            {
-                                if let Some(content_str) = content.as_str() {
-                                    if !callback(content_str.to_owned()).await {
-                                        break;
-                                    }
-                                    // if send.send(content_str.to_string()).await.is_err() {
-                                    //     break;
-                                    // }
-                                    // request_redraw();
-                                }
-                            }
-                        }
-                        Err(parse_err) => {
-                            log::error!(
-                                "copilot response is not valid json: {line:?} {parse_err:?}"
-                            );
-                        }
-                    }
-                }
-            }
-            Ok::<(), tokio::io::Error>(())
-        };
+        let headers = vec![
+            (
+                "Authorization".to_owned(),
+                format!("Bearer {}", config.token),
+            ),
+            ("x-ms-useragent".to_owned(), "Helix/0.1.0".to_owned()),
+            ("x-ms-user-agent".to_owned(), "Helix/0.1.0".to_owned()),
+            (
+                "Copilot-Integration-Id".to_owned(),
+                "vscode-chat".to_owned(),
+            ),
+        ];
 
-        let recv_stderr = async {
-            while let Some(line) = err_lines.next_line().await? {
-                log::error!("curl err {:?}", line);
-            }
-            Ok::<(), tokio::io::Error>(())
-        };
+        run_http_chat(
+            &format!("{}/chat/completions", config.endpoint),
+            &headers,
+            &request,
+            callback,
+        )
+        .await
+    }
+}
 
-        // Ensure all tasks are awaited properly and handle errors
-        let _ = tokio::try_join!(send_result, recv_result, recv_stderr).map_err(|e| {
-            log::error!("Error during curl execution: {:?}", e);
-        });
+/// The chat backend in use, selectable from config so people without
+/// Copilot access can point Helix at OpenAI, Anthropic, a local
+/// Ollama/llama.cpp endpoint, or an Azure deployment.
+#[derive(Clone)]
+pub struct ChatClient {
+    provider: Arc<dyn ChatProvider>,
+}
 
-        log::info!("wating for curl");
-        // Ensure the process is cleaned up properly
-        match child.wait().await {
-            Err(e) => {
-                log::error!("Error waiting for curl process: {:?}", e);
-            }
-            Ok(exit_status) => {
-                if !exit_status.success() {
-                    log::error!("curl error code: {:?}", exit_status);
-                }
-            }
-        };
+impl Default for ChatClient {
+    fn default() -> Self {
+        Self::new(Arc::new(CopilotProvider::default()))
+    }
+}
+
+impl ChatClient {
+    pub fn new(provider: Arc<dyn ChatProvider>) -> Self {
+        Self { provider }
+    }
+
+    pub fn from_provider_config(config: ProviderConfig) -> Self {
+        Self::new(Arc::new(GenericProvider::new(config)))
+    }
+
+    /// Builds the client the chat UI should use from the user's
+    /// [`ChatConfig`]: a [`GenericProvider`] if `chat.toml` configures one,
+    /// or the Copilot default otherwise.
+    pub fn from_config(config: &ChatConfig) -> Self {
+        match &config.provider {
+            Some(provider_config) => Self::from_provider_config(provider_config.clone()),
+            None => Self::default(),
+        }
+    }
+
+    /// Whether the configured backend is GitHub Copilot, so the system
+    /// prompt can drop Copilot-specific framing for any other provider.
+    pub fn is_copilot(&self) -> bool {
+        self.provider.is_copilot()
+    }
+
+    pub async fn send_chat(
+        &self,
+        messages: &[Message],
+        callback: ChatCallback,
+    ) -> Result<(), anyhow::Error> {
+        self.provider.send_chat(messages, callback).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_splitter_handles_split_lines() {
+        let mut splitter = LineSplitter::default();
+
+        assert_eq!(splitter.push(b"data: {\"foo\":"), Vec::<String>::new());
+        assert_eq!(
+            splitter.push(b"1}\ndata: [DON"),
+            vec!["data: {\"foo\":1}".to_owned()]
+        );
+        assert_eq!(splitter.push(b"E]\n"), vec!["data: [DONE]".to_owned()]);
     }
 }
@@ -0,0 +1,45 @@
+pub mod prompts;
+pub mod provider;
+
+use serde::{Deserialize, Serialize};
+
+use provider::ProviderConfig;
+
+/// Top-level chat configuration, loaded from `chat.toml` in the user's
+/// config directory.
+///
+/// `provider` selects the backend: left unset, chat falls back to GitHub
+/// Copilot (the original hardcoded behavior); setting it points Helix at
+/// any OpenAI-compatible endpoint (OpenAI, Anthropic, Azure, a local
+/// Ollama/llama.cpp server) instead, for users without Copilot access.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ChatConfig {
+    #[serde(default)]
+    pub provider: Option<ProviderConfig>,
+    /// Opts into the `#system` context provider, which runs shell commands
+    /// supplied inline in a chat message. Off by default since it lets
+    /// whatever's in the chat (the model's own suggestions included)
+    /// execute arbitrary commands.
+    #[serde(default)]
+    pub enable_system_context: bool,
+}
+
+impl ChatConfig {
+    /// Loads `chat.toml` from the config directory. Missing or unparsable
+    /// files fall back to the default (Copilot) configuration rather than
+    /// failing startup.
+    pub fn load() -> Self {
+        let path = helix_loader::config_dir().join("chat.toml");
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+        match toml::from_str(&contents) {
+            Ok(config) => config,
+            Err(err) => {
+                log::error!("failed to parse {}: {err}", path.display());
+                Self::default()
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
-pub const COPILOT_BASE: &'static str = r#"
-When asked for your name, you must respond with "GitHub Copilot".
+/// Provider-agnostic instructions: editor context and the code-change
+/// format every provider needs to follow, regardless of which backend is
+/// configured.
+pub const COMMON_BASE: &'static str = r#"
 Follow the user's requirements carefully & to the letter.
-Follow Microsoft content policies.
 Avoid content that violates copyrights.
 If you are asked to generate content that is harmful, hateful, racist, sexist, lewd, violent, or completely irrelevant to software engineering, only respond with "Sorry, I can't assist with that."
 Keep your answers short and impersonal.
@@ -28,18 +29,35 @@ When presenting code changes:
 6. If multiple changes are needed, present them as separate blocks with their own headers.
 "#;
 
-pub fn copilot_instructions() -> String {
+/// Copilot-specific framing, only accurate (and only sent) when the
+/// configured backend actually is GitHub Copilot.
+const COPILOT_FRAMING: &'static str = r#"
+When asked for your name, you must respond with "GitHub Copilot".
+Follow Microsoft content policies.
+"#;
+
+/// Builds the system prompt for a regular chat turn.
+///
+/// `is_copilot` should reflect [`ChatClient::is_copilot`](super::super::client::ChatClient::is_copilot);
+/// the Copilot naming/content-policy framing only applies when that backend
+/// is actually in use, so it's omitted for any other configured provider.
+pub fn chat_instructions(is_copilot: bool) -> String {
+    let framing = if is_copilot { COPILOT_FRAMING } else { "" };
     format!(
-        r#"You are a code-focused AI programming assistant that specializes in practical software engineering solutions.\n{}"#,
-        COPILOT_BASE
+        r#"You are a code-focused AI programming assistant that specializes in practical software engineering solutions.\n{framing}{}"#,
+        COMMON_BASE
     )
 }
 
-pub fn quick_copilot_instructions() -> String {
+/// Builds the system prompt for a quick chat turn, which additionally asks
+/// for just the updated selection rather than a full set of change blocks.
+pub fn quick_chat_instructions(is_copilot: bool) -> String {
+    let framing = if is_copilot { COPILOT_FRAMING } else { "" };
     format!(
         r#"You are a code-focused AI programming assistant that specializes in practical software engineering solutions.
-        {COPILOT_BASE}
+        {framing}{}
         Give only an updated version of the current selection with the request applied.
         "#,
+        COMMON_BASE,
     )
 }
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+/// How a [`super::super::client::ChatProvider`] authenticates its requests.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "scheme", rename_all = "snake_case")]
+pub enum AuthScheme {
+    /// No `Authorization` header is sent.
+    None,
+    /// `Authorization: Bearer <token>`.
+    Bearer { token: String },
+    /// An arbitrary `<name>: <value>` header, for APIs that don't use
+    /// `Authorization` (e.g. Anthropic's `x-api-key`).
+    Header { name: String, value: String },
+}
+
+impl Default for AuthScheme {
+    fn default() -> Self {
+        AuthScheme::None
+    }
+}
+
+/// Sampling parameters sent with every chat request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SamplingParams {
+    #[serde(default = "SamplingParams::default_temperature")]
+    pub temperature: f32,
+    #[serde(default = "SamplingParams::default_top_p")]
+    pub top_p: f32,
+    #[serde(default = "SamplingParams::default_n")]
+    pub n: u32,
+}
+
+impl SamplingParams {
+    fn default_temperature() -> f32 {
+        0.1
+    }
+
+    fn default_top_p() -> f32 {
+        1.0
+    }
+
+    fn default_n() -> u32 {
+        1
+    }
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            temperature: Self::default_temperature(),
+            top_p: Self::default_top_p(),
+            n: Self::default_n(),
+        }
+    }
+}
+
+/// Configuration for a chat backend that speaks an OpenAI-compatible chat
+/// completions API: OpenAI itself, a local Ollama/llama.cpp server, an Azure
+/// OpenAI deployment, or similar. Lets users without GitHub Copilot access
+/// still use the chat UI.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProviderConfig {
+    pub endpoint: String,
+    pub model: String,
+    #[serde(default)]
+    pub auth: AuthScheme,
+    #[serde(default)]
+    pub sampling: SamplingParams,
+}
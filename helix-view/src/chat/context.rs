@@ -1,4 +1,8 @@
-use std::{ops::RangeBounds, path::Path};
+use std::{
+    ops::RangeBounds,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use arc_swap::access::{DynAccess, DynGuard};
 use helix_core::{find_workspace, syntax::Loader};
@@ -25,7 +29,16 @@ fn path_relative_to_root(path: &Path) -> &Path {
 #[derive(Debug)]
 pub enum Context {
     Document { name: DocumentId },
+    /// A file attached by path rather than by open document, e.g. via the
+    /// `/file` picker. Unlike [`Context::Document`], resolving this doesn't
+    /// require the file to be open: [`FILE_CONTEXT`]'s resolver reads it
+    /// from disk when it isn't.
+    File { path: String },
     Selection,
+    Git { arg: String },
+    Buffers { arg: String },
+    System { command: String },
+    Project,
 }
 
 impl Context {
@@ -33,19 +46,65 @@ impl Context {
         Self::Document { name: doc }
     }
 
+    /// Whether this is the ambient [`Context::Project`] variant, used to
+    /// find and toggle it in a [`ChatState`](super::chat_state::ChatState)'s
+    /// context list without requiring `Context` to implement `PartialEq`.
+    pub fn is_project(&self) -> bool {
+        matches!(self, Context::Project)
+    }
+
+    /// The [`ContextProvider`] backing this variant, e.g. for displaying
+    /// its name and description without resolving it (and so without
+    /// running whatever side effect `#git`/`#system` resolution has).
+    pub fn provider(&self) -> &'static ContextProvider {
+        match self {
+            Context::Document { .. } => FILE_CONTEXT,
+            Context::File { .. } => FILE_CONTEXT,
+            Context::Selection => SELECTION_CONTEXT,
+            Context::Git { .. } => GIT_CONTEXT,
+            Context::Buffers { .. } => BUFFERS_CONTEXT,
+            Context::System { .. } => SYSTEM_CONTEXT,
+            Context::Project => PROJECT_CONTEXT,
+        }
+    }
+
+    /// The instance-specific detail to show alongside [`Self::provider`]'s
+    /// generic name/description, e.g. which file a `#file` entry points at,
+    /// so that e.g. two attached files don't render as identical lines in
+    /// `/context` (and `/drop <n>` has something to disambiguate against).
+    pub fn detail(&self, editor: &Editor) -> Option<String> {
+        match self {
+            Context::Document { name } => {
+                let doc = editor.document(*name)?;
+                let path = doc.path()?;
+                Some(path_relative_to_root(path).to_string_lossy().into_owned())
+            }
+            Context::File { path } => Some(path.clone()),
+            Context::Selection => None,
+            Context::Git { arg } => Some(arg.clone()),
+            Context::Buffers { arg } => Some(arg.clone()),
+            Context::System { command } => Some(command.clone()),
+            Context::Project => None,
+        }
+    }
+
     pub fn resolve(&self, editor: &Editor) -> Result<String, anyhow::Error> {
-        let (provider, arg) = match self {
+        let arg = match self {
             Context::Document { name } => {
                 let doc = editor.document(*name).unwrap();
                 let Some(path) = doc.path() else {
                     anyhow::bail!("file context only supports named files");
                 };
-                let path = path_relative_to_root(path);
-                (FILE_CONTEXT, path.to_string_lossy().into_owned())
+                path_relative_to_root(path).to_string_lossy().into_owned()
             }
-            Context::Selection => (SELECTION_CONTEXT, "".to_owned()),
+            Context::File { path } => path.clone(),
+            Context::Selection => "".to_owned(),
+            Context::Git { arg } => arg.clone(),
+            Context::Buffers { arg } => arg.clone(),
+            Context::System { command } => command.clone(),
+            Context::Project => "".to_owned(),
         };
-        (provider.prepare)(editor, &arg)
+        (self.provider().prepare)(editor, &arg)
     }
 }
 
@@ -111,3 +170,164 @@ pub const SELECTION_CONTEXT: &ContextProvider = &ContextProvider {
         Ok(text)
     },
 };
+
+pub const GIT_CONTEXT: &ContextProvider = &ContextProvider {
+    name: "git",
+    description: "Includes git information for the workspace. Input `staged` for `git diff --staged`, anything else for `git status`.",
+    prepare: |_ed, arg| {
+        let root = find_workspace().0;
+        let args: &[&str] = if arg.trim() == "staged" {
+            &["diff", "--staged"]
+        } else {
+            &["status"]
+        };
+
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&root)
+            .output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(format!("# GIT {} CONTEXT\n```diff\n{stdout}\n```\n\n", args.join(" ")))
+    },
+};
+
+fn document_content_block(doc: &crate::Document) -> String {
+    let name = doc
+        .path()
+        .map(|p| path_relative_to_root(p).to_string_lossy().into_owned())
+        .unwrap_or_else(|| doc.display_name().into_owned());
+    let file_type = doc.language_name().unwrap_or("");
+    let doc_text = generate_content_block(&doc.text().to_string(), ..);
+    format!("# FILE:{name} CONTEXT\n```{file_type}\n{doc_text}\n```\n\n")
+}
+
+pub const BUFFERS_CONTEXT: &ContextProvider = &ContextProvider {
+    name: "buffers",
+    description: "Includes content of open buffers. Input `visible` for only currently visible buffers, anything else for all open buffers.",
+    prepare: |ed, arg| {
+        if arg.trim() == "visible" {
+            let visible: std::collections::HashSet<_> =
+                ed.tree.views().map(|(view, _)| view.doc).collect();
+            Ok(ed
+                .documents()
+                .filter(|doc| visible.contains(&doc.id()))
+                .map(document_content_block)
+                .collect::<Vec<_>>()
+                .join("\n"))
+        } else {
+            Ok(ed
+                .documents()
+                .map(document_content_block)
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+    },
+};
+
+/// Whether the `#system` context provider is allowed to run shell commands.
+///
+/// Runs arbitrary commands supplied inline in a chat message, so this stays
+/// off unless a user has opted in via config; see [`set_system_context_enabled`].
+static SYSTEM_CONTEXT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_system_context_enabled(enabled: bool) {
+    SYSTEM_CONTEXT_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub const SYSTEM_CONTEXT: &ContextProvider = &ContextProvider {
+    name: "system",
+    description: "Runs a shell command and includes its stdout. Disabled by default, must be enabled in config.",
+    prepare: |_ed, arg| {
+        if !SYSTEM_CONTEXT_ENABLED.load(Ordering::Relaxed) {
+            anyhow::bail!(
+                "the #system context provider is disabled; enable it in config to allow running shell commands"
+            );
+        }
+
+        let output = std::process::Command::new("sh").arg("-c").arg(arg).output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "command `{arg}` failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(format!("# SYSTEM CONTEXT (`{arg}`)\n```\n{stdout}\n```\n\n"))
+    },
+};
+
+/// Lists every path under `root` that isn't excluded by `.gitignore` (or
+/// hidden), relative to `root`, one per line.
+fn project_tree(root: &Path) -> String {
+    ignore::WalkBuilder::new(root)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path() != root)
+        .map(|entry| {
+            entry
+                .path()
+                .strip_prefix(root)
+                .unwrap_or(entry.path())
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub const PROJECT_CONTEXT: &ContextProvider = &ContextProvider {
+    name: "project",
+    description: "Ambient summary of the workspace: the directory tree (honoring .gitignore) and the open buffers and their languages.",
+    prepare: |ed, _arg| {
+        let root = find_workspace().0;
+        let tree = project_tree(&root);
+
+        let open_docs = ed
+            .documents()
+            .map(|doc| {
+                let name = doc
+                    .path()
+                    .map(|p| path_relative_to_root(p).to_string_lossy().into_owned())
+                    .unwrap_or_else(|| doc.display_name().into_owned());
+                let lang = doc.language_name().unwrap_or("plain text");
+                format!("- {name} ({lang})")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if tree.is_empty() && open_docs.is_empty() {
+            return Ok(String::new());
+        }
+
+        let mut text = String::from("# PROJECT CONTEXT\n");
+        if !tree.is_empty() {
+            text.push_str(&format!("Workspace layout:\n```\n{tree}\n```\n"));
+        }
+        if !open_docs.is_empty() {
+            text.push_str(&format!("Open buffers:\n{open_docs}\n"));
+        }
+        text.push('\n');
+
+        Ok(text)
+    },
+};
+
+/// Every provider the prompt builder should advertise and be able to
+/// resolve. Extend this when adding a new `#<name>` context command.
+pub const ALL_CONTEXT_PROVIDERS: &[&ContextProvider] = &[
+    FILE_CONTEXT,
+    SELECTION_CONTEXT,
+    GIT_CONTEXT,
+    BUFFERS_CONTEXT,
+    SYSTEM_CONTEXT,
+    PROJECT_CONTEXT,
+];
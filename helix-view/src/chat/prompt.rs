@@ -1,6 +1,6 @@
 use crate::Editor;
 
-use super::{chat_state::Message, context::FILE_CONTEXT};
+use super::{chat_state::Message, context::ALL_CONTEXT_PROVIDERS};
 
 const HELP_MSG: &'static str = r#"When you need additional context, request it using this format:
 
@@ -32,7 +32,7 @@ pub fn format_prompt(
     let mut messages = Vec::new();
     let mut system_prompt = prompt.to_owned();
 
-    let enabled_contexts = &[FILE_CONTEXT];
+    let enabled_contexts = ALL_CONTEXT_PROVIDERS;
     if enabled_contexts.len() > 0 {
         let context_instructions = enabled_contexts
             .iter()
@@ -41,19 +41,18 @@ pub fn format_prompt(
             .join("\n\n");
         system_prompt = format!("{system_prompt}\n\n{HELP_MSG}\n{context_instructions}");
     }
-    messages.push(Message {
-        content: system_prompt,
-        role: "system".to_string(),
-    });
-    let context_msg = Message {
-        content: context
-            .iter()
-            .map(|item| item.resolve(editor))
-            .collect::<Result<Vec<_>, anyhow::Error>>()?
-            .join("\n"),
-        role: "system".to_owned(),
-    };
-    messages.push(context_msg);
+    messages.push(Message::new("system", system_prompt));
+    let context_text = context
+        .iter()
+        .map(|item| item.resolve(editor))
+        .collect::<Result<Vec<_>, anyhow::Error>>()?
+        .join("\n");
+    // Suppress the context message entirely rather than sending an empty
+    // system turn, e.g. when the only enabled context is `#project` and the
+    // workspace has no files and no open buffers.
+    if !context_text.is_empty() {
+        messages.push(Message::new("system", context_text));
+    }
     messages.extend(history.iter().cloned());
     Ok(messages)
 }